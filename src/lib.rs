@@ -1,10 +1,252 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
 use async_compression::tokio::bufread::{
-    BzDecoder, DeflateDecoder, GzipDecoder, XzDecoder, ZlibDecoder, ZstdDecoder,
+    BrotliDecoder, BzDecoder, DeflateDecoder, GzipDecoder, Lz4Decoder, XzDecoder, ZlibDecoder,
+    ZstdDecoder,
 };
 use async_compression::tokio::write::{
-    BzEncoder, DeflateEncoder, GzipEncoder, XzEncoder, ZlibEncoder, ZstdEncoder,
+    BrotliEncoder, BzEncoder, DeflateEncoder, GzipEncoder, Lz4Encoder, XzEncoder, ZlibEncoder,
+    ZstdEncoder,
 };
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use async_compression::Level;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+
+const BYTES_NEEDED: usize = 6;
+
+/// Tuning knobs for [`recompress_with_options`].
+///
+/// More knobs (e.g. a dictionary size) can be added here without changing
+/// the signature of `recompress_with_options`.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Compression level/quality passed to the chosen encoder. Defaults to
+    /// `Level::Default`, i.e. whatever async-compression picks for you.
+    pub level: Level,
+    /// If set, the encoded output must be at least this many percent
+    /// smaller than the uncompressed input or `recompress_with_options`
+    /// falls back to writing the plaintext through as `CompressionType::None`
+    /// instead of emitting a compressed stream that grew the data.
+    pub min_ratio: Option<u8>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            level: Level::Default,
+            min_ratio: None,
+        }
+    }
+}
+
+async fn encode_to_vec(
+    data: &[u8],
+    output_type: CompressionType,
+    level: Level,
+) -> std::io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+
+    match output_type {
+        CompressionType::Brotli => {
+            let mut encoder = BrotliEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Bzip => {
+            let mut encoder = BzEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Deflate => {
+            let mut encoder = DeflateEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Gzip => {
+            let mut encoder = GzipEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Lz4 => {
+            let mut encoder = Lz4Encoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Snappy => {
+            encoded = snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        CompressionType::Xz => {
+            let mut encoder = XzEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Zlib => {
+            let mut encoder = ZlibEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::Zstd => {
+            let mut encoder = ZstdEncoder::with_quality(&mut encoded, level);
+            encoder.write_all(data).await?;
+            encoder.shutdown().await?;
+        }
+        CompressionType::None => encoded.extend_from_slice(data),
+    }
+
+    Ok(encoded)
+}
+
+/// Whether `encoded` is at least `min_ratio` percent smaller than `decoded`.
+fn meets_min_ratio(decoded: &[u8], encoded: &[u8], min_ratio: u8) -> bool {
+    encoded.len() <= decoded.len()
+        && (decoded.len() - encoded.len()) * 100 >= decoded.len() * min_ratio as usize
+}
+
+/// Metadata recovered from a gzip header (RFC 1952). Surfaced by
+/// [`recompress`]/[`recompress_with_options`] whenever the input turns out
+/// to be gzip, regardless of `output_type`, so callers can log or re-apply
+/// it even when transcoding to a different format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzipMetadata {
+    pub filename: Option<String>,
+    pub comment: Option<String>,
+    pub mtime: u32,
+    pub os: u8,
+}
+
+const GZIP_FLAG_FHCRC: u8 = 0b0000_0010;
+const GZIP_FLAG_FEXTRA: u8 = 0b0000_0100;
+const GZIP_FLAG_FNAME: u8 = 0b0000_1000;
+const GZIP_FLAG_FCOMMENT: u8 = 0b0001_0000;
+
+/// Parses the fields of a gzip header off `stream` (which must be
+/// positioned at the very start of one, magic bytes included) and returns
+/// the metadata alongside every byte consumed, so the caller can replay
+/// them ahead of the still-untouched deflate body for `GzipDecoder`.
+async fn parse_gzip_header<S: AsyncRead + std::marker::Unpin>(
+    stream: &mut S,
+) -> std::io::Result<(GzipMetadata, Vec<u8>)> {
+    let mut consumed = [0u8; 10];
+    stream.read_exact(&mut consumed).await?;
+    let mut consumed = consumed.to_vec();
+
+    let flg = consumed[3];
+    let mtime = u32::from_le_bytes([consumed[4], consumed[5], consumed[6], consumed[7]]);
+    let os = consumed[9];
+
+    if flg & GZIP_FLAG_FEXTRA != 0 {
+        let mut xlen = [0u8; 2];
+        stream.read_exact(&mut xlen).await?;
+        consumed.extend_from_slice(&xlen);
+        let mut extra = vec![0u8; u16::from_le_bytes(xlen) as usize];
+        stream.read_exact(&mut extra).await?;
+        consumed.extend_from_slice(&extra);
+    }
+
+    let filename = if flg & GZIP_FLAG_FNAME != 0 {
+        Some(read_gzip_cstr(stream, &mut consumed).await?)
+    } else {
+        None
+    };
+
+    let comment = if flg & GZIP_FLAG_FCOMMENT != 0 {
+        Some(read_gzip_cstr(stream, &mut consumed).await?)
+    } else {
+        None
+    };
+
+    if flg & GZIP_FLAG_FHCRC != 0 {
+        let mut crc = [0u8; 2];
+        stream.read_exact(&mut crc).await?;
+        consumed.extend_from_slice(&crc);
+    }
+
+    Ok((
+        GzipMetadata {
+            filename,
+            comment,
+            mtime,
+            os,
+        },
+        consumed,
+    ))
+}
+
+async fn read_gzip_cstr<S: AsyncRead + std::marker::Unpin>(
+    stream: &mut S,
+    consumed: &mut Vec<u8>,
+) -> std::io::Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).await?;
+        consumed.push(byte[0]);
+        if byte[0] == 0 {
+            break;
+        }
+        bytes.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn gzip_header_bytes(metadata: &GzipMetadata) -> Vec<u8> {
+    let mut flg = 0u8;
+    if metadata.filename.is_some() {
+        flg |= GZIP_FLAG_FNAME;
+    }
+    if metadata.comment.is_some() {
+        flg |= GZIP_FLAG_FCOMMENT;
+    }
+
+    let mut header = vec![0x1f, 0x8b, 0x08, flg];
+    header.extend_from_slice(&metadata.mtime.to_le_bytes());
+    header.push(0); // XFL: async-compression's deflate body doesn't report an effort hint
+    header.push(metadata.os);
+
+    if let Some(filename) = &metadata.filename {
+        header.extend_from_slice(filename.as_bytes());
+        header.push(0);
+    }
+    if let Some(comment) = &metadata.comment {
+        header.extend_from_slice(comment.as_bytes());
+        header.push(0);
+    }
+
+    header
+}
+
+/// Writes a gzip stream carrying `metadata`'s header fields, since
+/// async-compression's `GzipEncoder` always writes a blank header. The
+/// deflate body itself still goes through async-compression; only the
+/// header/trailer are hand-rolled per RFC 1952.
+async fn encode_gzip_with_metadata<W: AsyncWrite + std::marker::Unpin + Send>(
+    data: &[u8],
+    output: &mut W,
+    metadata: &GzipMetadata,
+    level: Level,
+) -> std::io::Result<()> {
+    output.write_all(&gzip_header_bytes(metadata)).await?;
+
+    let mut body = Vec::new();
+    {
+        let mut encoder = DeflateEncoder::with_quality(&mut body, level);
+        encoder.write_all(data).await?;
+        encoder.shutdown().await?;
+    }
+    output.write_all(&body).await?;
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(data);
+    output.write_all(&crc.finalize().to_le_bytes()).await?;
+    output
+        .write_all(&(data.len() as u32).to_le_bytes())
+        .await?;
+
+    Ok(())
+}
 
 pub async fn recompress<
     'a,
@@ -14,74 +256,358 @@ pub async fn recompress<
     input: &mut R,
     output: &mut W,
     output_type: CompressionType,
-) -> std::io::Result<()> {
+) -> std::io::Result<Option<GzipMetadata>> {
+    recompress_with_options(input, output, output_type, Options::default()).await
+}
+
+pub async fn recompress_with_options<
+    R: AsyncRead + std::marker::Unpin + Send,
+    W: AsyncWrite + std::marker::Unpin + Send,
+>(
+    input: &mut R,
+    output: &mut W,
+    output_type: CompressionType,
+    options: Options,
+) -> std::io::Result<Option<GzipMetadata>> {
     let (input_type, magic) = detect_stream_characteristics(input).await?;
-    let input = &mut magic.chain(input);
+    let mut chained = magic.chain(input);
+
+    let (gzip_metadata, header_replay) = if input_type == CompressionType::Gzip {
+        let (metadata, header_bytes) = parse_gzip_header(&mut chained).await?;
+        (Some(metadata), Some(header_bytes))
+    } else {
+        (None, None)
+    };
 
-    if input_type == output_type {
-        tokio::io::copy(input, output).await?;
-        return Ok(());
+    let mut combined: Box<dyn AsyncRead + std::marker::Unpin + Send> = match header_replay {
+        Some(header_bytes) => Box::new(io::Cursor::new(header_bytes).chain(chained)),
+        None => Box::new(chained),
+    };
+
+    let reencode_needed = input_type != output_type
+        || !matches!(options.level, Level::Default)
+        || options.min_ratio.is_some();
+
+    if !reencode_needed {
+        tokio::io::copy(&mut combined, output).await?;
+        return Ok(gzip_metadata);
     }
 
     let mut decompressor: Box<dyn AsyncRead + std::marker::Unpin + Send> = match input_type {
-        CompressionType::Bzip => Box::new(BzDecoder::new(BufReader::new(input))),
-        CompressionType::Deflate => Box::new(DeflateDecoder::new(BufReader::new(input))),
-        CompressionType::Gzip => Box::new(GzipDecoder::new(BufReader::new(input))),
-        CompressionType::Xz => Box::new(XzDecoder::new(BufReader::new(input))),
-        CompressionType::Zlib => Box::new(ZlibDecoder::new(BufReader::new(input))),
-        CompressionType::Zstd => Box::new(ZstdDecoder::new(BufReader::new(input))),
-        CompressionType::None => Box::new(BufReader::new(input)),
+        CompressionType::Brotli => Box::new(BrotliDecoder::new(BufReader::new(combined))),
+        CompressionType::Bzip => Box::new(BzDecoder::new(BufReader::new(combined))),
+        CompressionType::Deflate => Box::new(DeflateDecoder::new(BufReader::new(combined))),
+        CompressionType::Gzip => Box::new(GzipDecoder::new(BufReader::new(combined))),
+        CompressionType::Lz4 => Box::new(Lz4Decoder::new(BufReader::new(combined))),
+        CompressionType::Snappy => {
+            unreachable!("detect_compression_type never returns Snappy")
+        }
+        CompressionType::Xz => Box::new(XzDecoder::new(BufReader::new(combined))),
+        CompressionType::Zlib => Box::new(ZlibDecoder::new(BufReader::new(combined))),
+        CompressionType::Zstd => Box::new(ZstdDecoder::new(BufReader::new(combined))),
+        CompressionType::None => Box::new(BufReader::new(combined)),
     };
 
+    if let Some(min_ratio) = options.min_ratio {
+        let mut decoded = Vec::new();
+        decompressor.read_to_end(&mut decoded).await?;
+
+        let encoded = match (output_type, &gzip_metadata) {
+            (CompressionType::Gzip, Some(metadata)) => {
+                let mut encoded = Vec::new();
+                encode_gzip_with_metadata(&decoded, &mut encoded, metadata, options.level).await?;
+                encoded
+            }
+            _ => encode_to_vec(&decoded, output_type, options.level).await?,
+        };
+
+        if meets_min_ratio(&decoded, &encoded, min_ratio) {
+            output.write_all(&encoded).await?;
+        } else {
+            output.write_all(&decoded).await?;
+        }
+        output.flush().await?;
+
+        return Ok(gzip_metadata);
+    }
+
+    let level = options.level;
+
+    if output_type == CompressionType::Gzip {
+        if let Some(metadata) = &gzip_metadata {
+            let mut decoded = Vec::new();
+            decompressor.read_to_end(&mut decoded).await?;
+            encode_gzip_with_metadata(&decoded, output, metadata, level).await?;
+            output.flush().await?;
+            return Ok(gzip_metadata);
+        }
+    }
+
     let mut recompressor: Box<dyn AsyncWrite + std::marker::Unpin + Send> = match output_type {
-        CompressionType::Bzip => Box::new(BzEncoder::new(output)),
-        CompressionType::Deflate => Box::new(DeflateEncoder::new(output)),
-        CompressionType::Gzip => Box::new(GzipEncoder::new(output)),
-        CompressionType::Xz => Box::new(XzEncoder::new(output)),
-        CompressionType::Zlib => Box::new(ZlibEncoder::new(output)),
-        CompressionType::Zstd => Box::new(ZstdEncoder::new(output)),
+        CompressionType::Brotli => Box::new(BrotliEncoder::with_quality(output, level)),
+        CompressionType::Bzip => Box::new(BzEncoder::with_quality(output, level)),
+        CompressionType::Deflate => Box::new(DeflateEncoder::with_quality(output, level)),
+        CompressionType::Gzip => Box::new(GzipEncoder::with_quality(output, level)),
+        CompressionType::Lz4 => Box::new(Lz4Encoder::with_quality(output, level)),
+        CompressionType::Snappy => {
+            // snap only exposes a synchronous raw block codec, so there's no
+            // `bufread`/`write` adapter to plug into the streaming pipeline
+            // above; buffer the fully-decoded input and compress it in one shot.
+            let mut decoded = Vec::new();
+            decompressor.read_to_end(&mut decoded).await?;
+            let compressed = snap::raw::Encoder::new()
+                .compress_vec(&decoded)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            output.write_all(&compressed).await?;
+            output.flush().await?;
+            return Ok(gzip_metadata);
+        }
+        CompressionType::Xz => Box::new(XzEncoder::with_quality(output, level)),
+        CompressionType::Zlib => Box::new(ZlibEncoder::with_quality(output, level)),
+        CompressionType::Zstd => Box::new(ZstdEncoder::with_quality(output, level)),
         CompressionType::None => Box::new(output),
     };
 
     tokio::io::copy(&mut decompressor, &mut recompressor).await?;
-    recompressor.flush().await?;
+    if output_type == CompressionType::None {
+        // No container to finalize, and `recompressor` here is just the
+        // caller's `output` itself — shutting it down would close a writer
+        // we don't own (e.g. a socket) for no reason.
+        recompressor.flush().await?;
+    } else {
+        // Every real codec needs `shutdown()`, not just `flush()`, to write
+        // its trailer (e.g. gzip's CRC32+ISIZE, xz's footer) — flushing alone
+        // leaves the stream truncated and undecodable.
+        recompressor.shutdown().await?;
+    }
 
-    Ok(())
+    Ok(gzip_metadata)
+}
+
+enum DecompressedReaderState<'a, R> {
+    Sniffing { inner: R, buffered: Vec<u8> },
+    Decoding(Pin<Box<dyn AsyncRead + Send + 'a>>),
+}
+
+/// An `AsyncRead` adapter that sniffs the compression format of the
+/// underlying stream from its magic bytes and transparently decompresses it,
+/// so callers that just want plaintext bytes don't need to know the
+/// container up front.
+///
+/// Streams shorter than [`BYTES_NEEDED`] bytes are treated as uncompressed
+/// and passed through losslessly.
+pub struct DecompressedReader<'a, R> {
+    state: Option<DecompressedReaderState<'a, R>>,
+}
+
+impl<'a, R: AsyncRead + std::marker::Unpin + Send + 'a> DecompressedReader<'a, R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            state: Some(DecompressedReaderState::Sniffing {
+                inner,
+                buffered: Vec::with_capacity(BYTES_NEEDED),
+            }),
+        }
+    }
+
+    fn decoder_for(
+        kind: CompressionType,
+        prefix: Vec<u8>,
+        inner: R,
+    ) -> Pin<Box<dyn AsyncRead + Send + 'a>> {
+        let input = BufReader::new(io::Cursor::new(prefix).chain(inner));
+        match kind {
+            CompressionType::Brotli => Box::pin(BrotliDecoder::new(input)),
+            CompressionType::Bzip => Box::pin(BzDecoder::new(input)),
+            CompressionType::Deflate => Box::pin(DeflateDecoder::new(input)),
+            CompressionType::Gzip => Box::pin(GzipDecoder::new(input)),
+            CompressionType::Lz4 => Box::pin(Lz4Decoder::new(input)),
+            CompressionType::Snappy => {
+                unreachable!("detect_compression_type never returns Snappy")
+            }
+            CompressionType::Xz => Box::pin(XzDecoder::new(input)),
+            CompressionType::Zlib => Box::pin(ZlibDecoder::new(input)),
+            CompressionType::Zstd => Box::pin(ZstdDecoder::new(input)),
+            CompressionType::None => Box::pin(input),
+        }
+    }
+}
+
+impl<'a, R: AsyncRead + std::marker::Unpin + Send + 'a> AsyncRead for DecompressedReader<'a, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            match self
+                .state
+                .take()
+                .expect("DecompressedReader polled after completion")
+            {
+                DecompressedReaderState::Sniffing {
+                    mut inner,
+                    mut buffered,
+                } => {
+                    let mut probe = [0u8; BYTES_NEEDED];
+                    let mut probe_buf = ReadBuf::new(&mut probe[..BYTES_NEEDED - buffered.len()]);
+                    match Pin::new(&mut inner).poll_read(cx, &mut probe_buf) {
+                        Poll::Ready(Ok(())) => {
+                            let n = probe_buf.filled().len();
+                            buffered.extend_from_slice(probe_buf.filled());
+
+                            if n == 0 || buffered.len() == BYTES_NEEDED {
+                                let kind = if buffered.len() < BYTES_NEEDED {
+                                    CompressionType::None
+                                } else {
+                                    detect_compression_type(&buffered)
+                                };
+
+                                self.state = Some(DecompressedReaderState::Decoding(
+                                    Self::decoder_for(kind, buffered, inner),
+                                ));
+                            } else {
+                                self.state =
+                                    Some(DecompressedReaderState::Sniffing { inner, buffered });
+                            }
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Pending => {
+                            self.state = Some(DecompressedReaderState::Sniffing { inner, buffered });
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                DecompressedReaderState::Decoding(mut decoder) => {
+                    let result = decoder.as_mut().poll_read(cx, buf);
+                    self.state = Some(DecompressedReaderState::Decoding(decoder));
+                    return result;
+                }
+            }
+        }
+    }
 }
 
 async fn detect_stream_characteristics<R: AsyncRead + std::marker::Unpin>(
     stream: &mut R,
 ) -> std::io::Result<(CompressionType, Vec<u8>)> {
-    let mut buffer = [0; 6];
-    let _n = stream.read(&mut buffer).await?;
+    let mut buffer = Vec::with_capacity(BYTES_NEEDED);
+
+    while buffer.len() < BYTES_NEEDED {
+        let mut chunk = [0; BYTES_NEEDED];
+        let n = stream.read(&mut chunk[..BYTES_NEEDED - buffer.len()]).await?;
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+
     let kind = detect_compression_type(&buffer);
 
-    Ok((kind, Vec::from(buffer)))
+    Ok((kind, buffer))
 }
 
-fn detect_compression_type(buffer: &[u8; 6]) -> CompressionType {
-    match buffer {
-        [0x28, 0xb5, 0x2f, 0xfd, _, _] => CompressionType::Zstd,
-        [0x1f, 0x8b, _, _, _, _] => CompressionType::Gzip,
-        [0x78, 0x01, _, _, _, _] => CompressionType::Deflate,
-        [0x78, 0x9c, _, _, _, _] => CompressionType::Zlib,
-        [0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00] => CompressionType::Xz,
-        [0x42, 0x5a, 0x68, _, _, _] => CompressionType::Bzip,
-        _ => CompressionType::None,
+fn detect_compression_type(buffer: &[u8]) -> CompressionType {
+    // Brotli and raw Snappy have no stable magic bytes, so they're never
+    // auto-detected here; pass `output_type` explicitly for those.
+    if buffer.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        CompressionType::Zstd
+    } else if buffer.starts_with(&[0x1f, 0x8b]) {
+        CompressionType::Gzip
+    } else if buffer.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        CompressionType::Lz4
+    } else if buffer.starts_with(&[0x78, 0x01]) {
+        CompressionType::Deflate
+    } else if buffer.starts_with(&[0x78, 0x9c]) {
+        CompressionType::Zlib
+    } else if buffer.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
+        CompressionType::Xz
+    } else if buffer.starts_with(&[0x42, 0x5a, 0x68]) {
+        CompressionType::Bzip
+    } else {
+        CompressionType::None
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy)]
 pub enum CompressionType {
+    Brotli,
     Bzip,
     Deflate, //
     Gzip,    //
+    Lz4,     //
+    Snappy,  //
     Xz,      //
     Zlib,    //
     Zstd,    //
     None,    //
 }
 
+impl std::fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CompressionType::Brotli => "brotli",
+            CompressionType::Bzip => "bzip2",
+            CompressionType::Deflate => "deflate",
+            CompressionType::Gzip => "gzip",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Snappy => "snappy",
+            CompressionType::Xz => "xz",
+            CompressionType::Zlib => "zlib",
+            CompressionType::Zstd => "zstd",
+            CompressionType::None => "none",
+        })
+    }
+}
+
+/// Error returned by [`CompressionType::from_str`] for an unrecognized name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCompressionTypeError(String);
+
+impl std::fmt::Display for ParseCompressionTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unrecognized compression type: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCompressionTypeError {}
+
+impl std::str::FromStr for CompressionType {
+    type Err = ParseCompressionTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "brotli" | "br" => Ok(CompressionType::Brotli),
+            "bzip" | "bzip2" | "bz2" => Ok(CompressionType::Bzip),
+            "deflate" => Ok(CompressionType::Deflate),
+            "gzip" | "gz" => Ok(CompressionType::Gzip),
+            "lz4" => Ok(CompressionType::Lz4),
+            "snappy" | "snap" => Ok(CompressionType::Snappy),
+            "xz" => Ok(CompressionType::Xz),
+            "zlib" | "zz" => Ok(CompressionType::Zlib),
+            "zstd" | "zst" => Ok(CompressionType::Zstd),
+            "none" => Ok(CompressionType::None),
+            other => Err(ParseCompressionTypeError(other.to_string())),
+        }
+    }
+}
+
+impl CompressionType {
+    /// Infers a compression type from a filename extension, e.g. for
+    /// "recompress into whatever `output_path` implies" callers. Returns
+    /// `None` for unrecognized or missing extensions.
+    pub fn from_extension(path: &std::path::Path) -> Option<CompressionType> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(CompressionType::Gzip),
+            "xz" => Some(CompressionType::Xz),
+            "bz2" => Some(CompressionType::Bzip),
+            "zst" => Some(CompressionType::Zstd),
+            "zz" => Some(CompressionType::Zlib),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::io::Result;
@@ -115,7 +641,7 @@ mod test {
         {
             let mut encoder = ZstdEncoder::new(&mut compressed_stream);
             encoder.write_all(expected.as_bytes()).await?;
-            encoder.flush().await?;
+            encoder.shutdown().await?;
         }
 
         assert!(!compressed_stream.is_empty());
@@ -137,7 +663,7 @@ mod test {
         {
             let mut encoder = GzipEncoder::new(&mut compressed_stream);
             encoder.write_all(expected.as_bytes()).await?;
-            encoder.flush().await?;
+            encoder.shutdown().await?;
         }
 
         assert!(!compressed_stream.is_empty());
@@ -164,7 +690,7 @@ mod test {
         {
             let mut encoder = DeflateEncoder::new(&mut compressed_stream);
             encoder.write_all(expected.as_bytes()).await?;
-            encoder.flush().await?;
+            encoder.shutdown().await?;
         }
 
         assert!(!compressed_stream.is_empty());
@@ -186,7 +712,7 @@ mod test {
         {
             let mut encoder = ZlibEncoder::new(&mut compressed_stream);
             encoder.write_all(expected.as_bytes()).await?;
-            encoder.flush().await?;
+            encoder.shutdown().await?;
         }
 
         assert!(!compressed_stream.is_empty());
@@ -208,7 +734,7 @@ mod test {
         {
             let mut encoder = XzEncoder::new(&mut compressed_stream);
             encoder.write_all(expected.as_bytes()).await?;
-            encoder.flush().await?;
+            encoder.shutdown().await?;
         }
 
         assert!(!compressed_stream.is_empty());
@@ -217,4 +743,411 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_decompressed_reader_sniffs_zstd() -> Result<()> {
+        let expected = "this is a test";
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = ZstdEncoder::new(&mut compressed_stream);
+            encoder.write_all(expected.as_bytes()).await?;
+            encoder.shutdown().await?;
+        }
+
+        let mut reader = DecompressedReader::new(compressed_stream.as_slice());
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).await?;
+
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decompressed_reader_passes_through_short_streams() -> Result<()> {
+        let expected = "hi";
+
+        let mut reader = DecompressedReader::new(expected.as_bytes());
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).await?;
+
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decompressed_reader_passes_through_uncompressed() -> Result<()> {
+        let expected = "this is a longer uncompressed test string";
+
+        let mut reader = DecompressedReader::new(expected.as_bytes());
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).await?;
+
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    /// An `AsyncRead` that only ever returns up to two bytes per `poll_read`,
+    /// simulating a slow socket or pipe that splits up a magic header.
+    struct TwoByteReader {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl AsyncRead for TwoByteReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<Result<()>> {
+            let remaining = &self.data[self.pos..];
+            let n = remaining.len().min(2).min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            self.pos += n;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recompress_detects_magic_across_short_reads() -> Result<()> {
+        let expected = "this is a test";
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = GzipEncoder::new(&mut compressed_stream);
+            encoder.write_all(expected.as_bytes()).await?;
+            encoder.shutdown().await?;
+        }
+
+        let mut input_stream = TwoByteReader {
+            data: compressed_stream.clone(),
+            pos: 0,
+        };
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress(&mut input_stream, &mut output_stream, CompressionType::Gzip).await?;
+
+        assert_eq!(compressed_stream, output_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recompress_with_options_honors_level() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress_with_options(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Xz,
+            Options {
+                level: Level::Fastest,
+                ..Options::default()
+            },
+        )
+        .await?;
+
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        recompress(
+            &mut output_stream.as_slice(),
+            &mut decompressed_stream,
+            CompressionType::None,
+        )
+        .await?;
+
+        let mut fastest_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = XzEncoder::with_quality(&mut fastest_stream, Level::Fastest);
+            encoder.write_all(expected.as_bytes()).await?;
+            encoder.shutdown().await?;
+        }
+
+        assert!(!output_stream.is_empty());
+        assert_eq!(fastest_stream, output_stream);
+        assert_eq!(expected.as_bytes(), decompressed_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lz4_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress(&mut input_stream, &mut output_stream, CompressionType::Lz4).await?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = Lz4Encoder::new(&mut compressed_stream);
+            encoder.write_all(expected.as_bytes()).await?;
+            encoder.shutdown().await?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_brotli_compression_works() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Brotli,
+        )
+        .await?;
+
+        let mut compressed_stream: Vec<u8> = Vec::new();
+        {
+            let mut encoder = BrotliEncoder::new(&mut compressed_stream);
+            encoder.write_all(expected.as_bytes()).await?;
+            encoder.shutdown().await?;
+        }
+
+        assert!(!compressed_stream.is_empty());
+        assert_eq!(compressed_stream, output_stream);
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snappy_compression_round_trips() -> Result<()> {
+        let expected = "this is a test";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Snappy,
+        )
+        .await?;
+
+        assert!(!output_stream.is_empty());
+        assert_ne!(expected.as_bytes(), output_stream);
+
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(&output_stream)
+            .expect("valid raw snappy block");
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_min_ratio_falls_back_to_stored_for_tiny_input() -> Result<()> {
+        // A couple of bytes can't outweigh xz's container overhead, so a
+        // strict min_ratio should make us store the plaintext instead of
+        // emitting an xz stream that's bigger than the input.
+        let expected = "hi";
+        let mut input_stream = expected.as_bytes();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress_with_options(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Xz,
+            Options {
+                min_ratio: Some(10),
+                ..Options::default()
+            },
+        )
+        .await?;
+
+        assert_eq!(expected.as_bytes(), output_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_min_ratio_keeps_compressed_output_when_ratio_met() -> Result<()> {
+        let expected: Vec<u8> = vec![b'a'; 10_000];
+        let mut input_stream = expected.as_slice();
+        let mut output_stream: Vec<u8> = Vec::new();
+
+        recompress_with_options(
+            &mut input_stream,
+            &mut output_stream,
+            CompressionType::Xz,
+            Options {
+                min_ratio: Some(10),
+                ..Options::default()
+            },
+        )
+        .await?;
+
+        assert_ne!(expected, output_stream);
+        assert!(output_stream.len() < expected.len());
+
+        let mut decompressed_stream: Vec<u8> = Vec::new();
+        recompress(
+            &mut output_stream.as_slice(),
+            &mut decompressed_stream,
+            CompressionType::None,
+        )
+        .await?;
+
+        assert_eq!(expected, decompressed_stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gzip_metadata_round_trips_on_gzip_to_gzip_recompress() -> Result<()> {
+        // Long and repetitive enough that Level::Default and Level::Best
+        // actually produce different deflate bodies; a short fixture like
+        // "this is a test" compresses identically at both levels.
+        let expected = "this is a test ".repeat(200);
+        let metadata = GzipMetadata {
+            filename: Some("hello.txt".to_string()),
+            comment: Some("a test comment".to_string()),
+            mtime: 12345,
+            os: 3,
+        };
+
+        let mut input_stream = Vec::new();
+        encode_gzip_with_metadata(expected.as_bytes(), &mut input_stream, &metadata, Level::Default)
+            .await?;
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = recompress_with_options(
+            &mut input_stream.as_slice(),
+            &mut output_stream,
+            CompressionType::Gzip,
+            Options {
+                level: Level::Best,
+                ..Options::default()
+            },
+        )
+        .await?;
+
+        assert_eq!(Some(metadata.clone()), result);
+        assert_ne!(input_stream, output_stream);
+
+        let mut decompressed = Vec::new();
+        let roundtrip_metadata = recompress(
+            &mut output_stream.as_slice(),
+            &mut decompressed,
+            CompressionType::None,
+        )
+        .await?;
+
+        assert_eq!(expected.as_bytes(), decompressed);
+        assert_eq!(Some(metadata), roundtrip_metadata);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gzip_metadata_is_surfaced_even_for_non_gzip_output() -> Result<()> {
+        let expected = "this is a test";
+        let metadata = GzipMetadata {
+            filename: Some("data.bin".to_string()),
+            comment: None,
+            mtime: 0,
+            os: 255,
+        };
+
+        let mut input_stream = Vec::new();
+        encode_gzip_with_metadata(expected.as_bytes(), &mut input_stream, &metadata, Level::Default)
+            .await?;
+
+        let mut output_stream: Vec<u8> = Vec::new();
+        let result = recompress(
+            &mut input_stream.as_slice(),
+            &mut output_stream,
+            CompressionType::Xz,
+        )
+        .await?;
+
+        assert_eq!(Some(metadata), result);
+
+        let mut decompressed = Vec::new();
+        recompress(
+            &mut output_stream.as_slice(),
+            &mut decompressed,
+            CompressionType::None,
+        )
+        .await?;
+        assert_eq!(expected.as_bytes(), decompressed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_type_display_round_trips_through_from_str() {
+        use std::str::FromStr;
+
+        for kind in [
+            CompressionType::Brotli,
+            CompressionType::Bzip,
+            CompressionType::Deflate,
+            CompressionType::Gzip,
+            CompressionType::Lz4,
+            CompressionType::Snappy,
+            CompressionType::Xz,
+            CompressionType::Zlib,
+            CompressionType::Zstd,
+            CompressionType::None,
+        ] {
+            assert_eq!(kind, CompressionType::from_str(&kind.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_compression_type_from_str_accepts_aliases() {
+        use std::str::FromStr;
+
+        assert_eq!(CompressionType::Gzip, CompressionType::from_str("gz").unwrap());
+        assert_eq!(
+            CompressionType::Bzip,
+            CompressionType::from_str("BZ2").unwrap()
+        );
+        assert_eq!(
+            CompressionType::Zstd,
+            CompressionType::from_str("zst").unwrap()
+        );
+        assert!(CompressionType::from_str("made-up").is_err());
+    }
+
+    #[test]
+    fn test_compression_type_from_extension() {
+        use std::path::Path;
+
+        assert_eq!(
+            Some(CompressionType::Gzip),
+            CompressionType::from_extension(Path::new("archive.tar.gz"))
+        );
+        assert_eq!(
+            Some(CompressionType::Xz),
+            CompressionType::from_extension(Path::new("archive.tar.xz"))
+        );
+        assert_eq!(
+            Some(CompressionType::Bzip),
+            CompressionType::from_extension(Path::new("archive.bz2"))
+        );
+        assert_eq!(
+            Some(CompressionType::Zstd),
+            CompressionType::from_extension(Path::new("archive.zst"))
+        );
+        assert_eq!(
+            Some(CompressionType::Zlib),
+            CompressionType::from_extension(Path::new("data.zz"))
+        );
+        assert_eq!(None, CompressionType::from_extension(Path::new("plain.txt")));
+        assert_eq!(None, CompressionType::from_extension(Path::new("noext")));
+    }
 }